@@ -5,7 +5,9 @@ of a dice roll expressed in D&D notation.
 */
 
 extern crate getopts;
+extern crate rand;
 
+use std::collections::HashMap;
 use std::env::args;
 use std::error::Error;
 use std::fmt;
@@ -14,6 +16,7 @@ use std::io::Write;
 use std::process;
 
 use getopts::Options;
+use rand::Rng;
 
 const MAX_DIGITS: usize = 5; // Max number of digits in a number
 
@@ -25,6 +28,10 @@ const MAX_DIGITS: usize = 5; // Max number of digits in a number
 pub enum OutputStyle {
     SingleLine,
     MultiLine,
+    /// Each possible total with its probability and a histogram.
+    Distribution,
+    /// A simulated roll: the individual dice and the total.
+    Roll,
 }
 
 /// The types of errors that can occur in ev:
@@ -42,7 +49,20 @@ pub enum OutputStyle {
 /// - ExtraTooLarge:
 ///     if the bonus/malus of a roll is not between
 ///     -2^15 and 2^15 - 1.
-#[derive(Debug, PartialEq)]
+/// - EmptyTerm:
+///     if a `+` or `-` is followed by something that is
+///     not a dice term or a constant (e.g. `2d6+x`).
+/// - ThresholdOutOfRange:
+///     if a dice-pool success threshold is not between 1 and
+///     the number of faces, or an explosion threshold is not
+///     between 2 and the number of faces.
+/// - KeepTooLarge:
+///     if a keep/drop modifier asks to keep or drop more dice
+///     than the roll has.
+/// - UndefinedVariable:
+///     if a roll refers to a named variable that was not
+///     supplied with `-D name=value`.
+#[derive(Debug, PartialEq, Clone)]
 pub enum EvError {
     InvalidFormat,
     MissingNumberOfDice,
@@ -51,6 +71,10 @@ pub enum EvError {
     TooManyDice,
     TooManySides,
     ExtraTooLarge,
+    EmptyTerm,
+    ThresholdOutOfRange,
+    KeepTooLarge,
+    UndefinedVariable(String),
 }
 
 impl Error for EvError {
@@ -63,16 +87,38 @@ impl Error for EvError {
             EvError::TooManyDice => "too many dice",
             EvError::TooManySides => "too many sides",
             EvError::ExtraTooLarge => "bonus too large",
+            EvError::EmptyTerm => "empty term",
+            EvError::ThresholdOutOfRange => "threshold out of range",
+            EvError::KeepTooLarge => "keep count exceeds dice count",
+            EvError::UndefinedVariable(_) => "undefined variable",
         }
     }
 }
 
 impl fmt::Display for EvError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.description())
+        match *self {
+            EvError::UndefinedVariable(ref name) =>
+                write!(f, "undefined variable: {}", name),
+            ref other => f.write_str(other.description()),
+        }
     }
 }
 
+/// Which of the rolled dice contribute to the total.
+///
+/// `All` sums every die (the default). `Highest(n)` keeps the
+/// `n` largest dice and `Lowest(n)` the `n` smallest — the basis
+/// of `4d6k3` ability-score generation and 5e advantage
+/// (`2d20k1`). Drop modifiers are normalized into these: dropping
+/// the lowest of `m` dice keeps the highest `m - 1`, and so on.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Keep {
+    All,
+    Highest(u16),
+    Lowest(u16),
+}
+
 /// A dice roll.
 ///
 /// A dice roll has three components:
@@ -80,23 +126,34 @@ impl fmt::Display for EvError {
 /// - A number of dice (positive integer);
 /// - A number of faces (positive integer);
 /// - An extra (e.g., +3 or -4).
+///
+/// plus an optional keep/drop modifier selecting which dice count
+/// towards the total.
 #[derive(Debug, PartialEq)]
 pub struct Roll {
     num_dice: u16,
     num_faces: u16,
     extra: i16,
+    keep: Keep,
 }
 
 impl Roll {
-    /// Create a new roll.
+    /// Create a new roll that keeps every die.
     pub fn new(num_dice: u16, num_faces: u16, extra: i16) -> Self {
         Roll {
             num_dice: num_dice,
             num_faces: num_faces,
             extra: extra,
+            keep: Keep::All,
         }
     }
 
+    /// Attach a keep/drop modifier to the roll.
+    pub fn with_keep(mut self, keep: Keep) -> Self {
+        self.keep = keep;
+        self
+    }
+
     // A small helper method to extract the integer
     // fields as floats for making calculations.
     fn float_values(&self) -> (f32, f32, f32) {
@@ -105,9 +162,19 @@ impl Roll {
          self.extra as f32)
     }
 
-    /// Compute the expected value: expected value of one die
-    /// multiplied by the number of dice, then add the extra.
+    /// Compute the expected value.
+    ///
+    /// Without a keep/drop modifier this is the closed form
+    /// (expected value of one die times the number of dice, plus
+    /// the extra); with one it is derived from the exact
+    /// order-statistics distribution.
     pub fn ev(&self) -> f32 {
+        if self.keep != Keep::All {
+            return self.distribution()
+                .iter()
+                .map(|&(s, p)| s as f64 * p)
+                .sum::<f64>() as f32;
+        }
         // Math reminder:
         // 1 + 2 + ... + n = n(n+1) / 2
         // therefore
@@ -117,38 +184,531 @@ impl Roll {
         nd * single_die_ev + extra
     }
 
+    /// Simulate rolling the dice, returning the value of each
+    /// individual die. The constant `extra` is not included; the
+    /// RNG is injectable so callers (and tests) control randomness.
+    pub fn roll<R: Rng>(&self, rng: &mut R) -> Vec<u16> {
+        (0 .. self.num_dice)
+            .map(|_| rng.gen_range(1 ..= self.num_faces))
+            .collect()
+    }
+
+    // Which of the just-rolled dice the keep/drop modifier counts,
+    // as a boolean mask aligned with `rolled`. `Highest(n)` keeps
+    // the `n` largest values and `Lowest(n)` the `n` smallest;
+    // `All` keeps everything.
+    fn kept_mask(&self, rolled: &[u16]) -> Vec<bool> {
+        let n = rolled.len();
+        let count = match self.keep {
+            Keep::All => return vec![true; n],
+            Keep::Highest(k) | Keep::Lowest(k) => (k as usize).min(n),
+        };
+        // Order the dice by value; the kept subset is a slice of
+        // the sorted indices from one end or the other.
+        let mut idx: Vec<usize> = (0 .. n).collect();
+        idx.sort_by_key(|&i| rolled[i]);
+        let kept = match self.keep {
+            Keep::Highest(_) => &idx[n - count ..],
+            Keep::Lowest(_) => &idx[.. count],
+            Keep::All => unreachable!(),
+        };
+        let mut mask = vec![false; n];
+        for &i in kept {
+            mask[i] = true;
+        }
+        mask
+    }
+
     /// Compute the minimum value.
     pub fn min(&self) -> f32 {
+        if self.keep != Keep::All {
+            return self.distribution().first().unwrap().0 as f32;
+        }
         let (nd, _, extra) = self.float_values();
         nd + extra
     }
 
     /// Compute the maximum value.
     pub fn max(&self) -> f32 {
+        if self.keep != Keep::All {
+            return self.distribution().last().unwrap().0 as f32;
+        }
         let (nd, nf, extra) = self.float_values();
         nd * nf + extra
     }
 
+    /// Compute the variance of the roll.
+    ///
+    /// A single `dY` has variance `(num_faces² − 1)/12`; for
+    /// independent dice this scales linearly, and the constant
+    /// `extra` does not affect it. With a keep/drop modifier the
+    /// dice are no longer independent, so the variance is read off
+    /// the exact distribution instead.
+    pub fn variance(&self) -> f64 {
+        if self.keep != Keep::All {
+            let dist = self.distribution();
+            let mean: f64 = dist.iter().map(|&(s, p)| s as f64 * p).sum();
+            let m2: f64 = dist.iter().map(|&(s, p)| (s as f64).powi(2) * p).sum();
+            return m2 - mean * mean;
+        }
+        let nd = self.num_dice as f64;
+        let nf = self.num_faces as f64;
+        nd * (nf * nf - 1.0) / 12.0
+    }
+
+    /// Compute the standard deviation of the roll.
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Compute the exact probability distribution of the roll.
+    ///
+    /// Returns a `(total, probability)` pair for every total the
+    /// roll can produce, in increasing order. For a plain roll the
+    /// distribution is built by dynamic-programming convolution:
+    /// start from the distribution of zero dice (the sum 0 with
+    /// probability 1), fold in one `dY` at a time (each face
+    /// 1..=num_faces has probability 1/num_faces). With a
+    /// keep/drop modifier it is the order-statistics distribution
+    /// of the kept sum. Either way every outcome is finally shifted
+    /// by the extra.
+    pub fn distribution(&self) -> Vec<(i32, f64)> {
+        let base = match self.keep {
+            Keep::All => self.sum_distribution(),
+            Keep::Highest(k) =>
+                kept_sum_distribution(self.num_dice, self.num_faces, k, true),
+            Keep::Lowest(k) =>
+                kept_sum_distribution(self.num_dice, self.num_faces, k, false),
+        };
+        base.into_iter()
+            .filter(|&(_, prob)| prob > 0.0)
+            .map(|(s, prob)| (s + self.extra as i32, prob))
+            .collect()
+    }
+
+    // Distribution of the plain sum of all the dice (no extra).
+    fn sum_distribution(&self) -> Vec<(i32, f64)> {
+        let nf = self.num_faces as usize;
+        let p = 1.0 / nf as f64;
+
+        // dist[s] is the probability of the partial sum being `s`.
+        let mut dist = vec![1.0f64];
+        for _ in 0 .. self.num_dice {
+            let mut next = vec![0.0f64; dist.len() + nf];
+            for (s, &ps) in dist.iter().enumerate() {
+                if ps == 0.0 { continue; }
+                for f in 1 ..= nf {
+                    next[s + f] += ps * p;
+                }
+            }
+            dist = next;
+        }
+
+        dist.into_iter().enumerate().map(|(s, prob)| (s as i32, prob)).collect()
+    }
+
+}
+
+/// Convert a roll into the `XdY+Z` notation, with any keep
+/// modifier (`kN` or `klN`) written right after the sides.
+impl fmt::Display for Roll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}d{}", self.num_dice, self.num_faces)?;
+        match self.keep {
+            Keep::All => {}
+            Keep::Highest(k) => write!(f, "k{}", k)?,
+            Keep::Lowest(k) => write!(f, "kl{}", k)?,
+        }
+        if self.extra != 0 {
+            write!(f, "{:+}", self.extra)?;
+        }
+        return Ok(());
+    }
+}
+
+/// A dice expression.
+///
+/// A dice expression is one or more dice rolls and integer
+/// constants joined by `+` and `-`, e.g. `2d6 + 1d8 + 3` or
+/// `4d6 - 1d4`. A single `Roll` is one node; compound
+/// expressions are built by joining nodes with `Add`/`Sub`.
+///
+/// Because the terms are independent, `min`, `max` and `ev`
+/// are additive and computed by summing each side's
+/// contribution (with the usual sign flip for the right side
+/// of a subtraction).
+#[derive(Debug, PartialEq)]
+pub enum Expr {
+    Dice(Roll),
+    Constant(i16),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Compute the expected value of the whole expression.
+    pub fn ev(&self) -> f32 {
+        match *self {
+            Expr::Dice(ref r) => r.ev(),
+            Expr::Constant(c) => c as f32,
+            Expr::Add(ref a, ref b) => a.ev() + b.ev(),
+            Expr::Sub(ref a, ref b) => a.ev() - b.ev(),
+        }
+    }
+
+    /// Compute the minimum value of the whole expression.
+    pub fn min(&self) -> f32 {
+        match *self {
+            Expr::Dice(ref r) => r.min(),
+            Expr::Constant(c) => c as f32,
+            Expr::Add(ref a, ref b) => a.min() + b.min(),
+            // Subtraction is smallest when the subtrahend is largest.
+            Expr::Sub(ref a, ref b) => a.min() - b.max(),
+        }
+    }
+
+    /// Compute the maximum value of the whole expression.
+    pub fn max(&self) -> f32 {
+        match *self {
+            Expr::Dice(ref r) => r.max(),
+            Expr::Constant(c) => c as f32,
+            Expr::Add(ref a, ref b) => a.max() + b.max(),
+            // Subtraction is largest when the subtrahend is smallest.
+            Expr::Sub(ref a, ref b) => a.max() - b.min(),
+        }
+    }
+
+    /// Compute the exact probability distribution of the whole
+    /// expression by convolving the distributions of its terms,
+    /// negating the right-hand side of a subtraction.
+    pub fn distribution(&self) -> Vec<(i32, f64)> {
+        match *self {
+            Expr::Dice(ref r) => r.distribution(),
+            Expr::Constant(c) => vec![(c as i32, 1.0)],
+            Expr::Add(ref a, ref b) =>
+                convolve(&a.distribution(), &b.distribution(), 1),
+            Expr::Sub(ref a, ref b) =>
+                convolve(&a.distribution(), &b.distribution(), -1),
+        }
+    }
+
+    /// Simulate the whole expression and return the grand total.
+    /// The RNG is injectable so tests can pass a seeded generator
+    /// for deterministic output.
+    pub fn roll<R: Rng>(&self, rng: &mut R) -> i32 {
+        let mut dice = Vec::new();
+        self.roll_into(rng, &mut dice)
+    }
+
+    /// Simulate the expression and describe every die group next
+    /// to the grand total, e.g. `2d6+3: [4, 1] +3 => 8`.
+    pub fn roll_report<R: Rng>(&self, rng: &mut R) -> String {
+        let mut dice = Vec::new();
+        let total = self.roll_into(rng, &mut dice);
+        format!("{}: {} => {}", self, dice.join(" "), total)
+    }
+
+    // Walk the expression once, pushing a textual description of
+    // each term into `dice` and returning the signed total.
+    fn roll_into<R: Rng>(&self, rng: &mut R, dice: &mut Vec<String>) -> i32 {
+        match *self {
+            Expr::Dice(ref r) => {
+                let rolled = r.roll(rng);
+                let mask = r.kept_mask(&rolled);
+                let sum: i32 = rolled.iter().zip(&mask)
+                    .filter(|&(_, &keep)| keep)
+                    .map(|(&d, _)| d as i32)
+                    .sum();
+                dice.push(format_rolled(&rolled, &mask));
+                sum + r.extra as i32
+            }
+            Expr::Constant(c) => {
+                dice.push(format!("{:+}", c));
+                c as i32
+            }
+            Expr::Add(ref a, ref b) =>
+                a.roll_into(rng, dice) + b.roll_into(rng, dice),
+            Expr::Sub(ref a, ref b) =>
+                a.roll_into(rng, dice) - b.roll_into(rng, dice),
+        }
+    }
+
+    /// Compute the variance of the whole expression. Independent
+    /// terms add their variances (a constant contributes none, and
+    /// subtraction adds just like addition).
+    pub fn variance(&self) -> f64 {
+        match *self {
+            Expr::Dice(ref r) => r.variance(),
+            Expr::Constant(_) => 0.0,
+            Expr::Add(ref a, ref b) => a.variance() + b.variance(),
+            Expr::Sub(ref a, ref b) => a.variance() + b.variance(),
+        }
+    }
+
+    /// Compute the standard deviation of the whole expression.
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
     /// Display the roll statistics on a single line.
     /// Useful for usage in a Unix pipe-line.
     pub fn print(&self) -> String {
-        format!("{} {} {} {}", self, self.min(), self.max(), self.ev())
+        format!("{} {} {} {} {} {}",
+                self, self.min(), self.max(), self.ev(),
+                self.variance(), self.stddev())
     }
 
     /// Display the roll statistics on multiple lines.
     /// Prettier to look at for a human.
     pub fn pretty_print(&self) -> String {
-        format!("{}:\n\tmin: {}\n\tmax: {}\n\tev : {}",
-                self, self.min(), self.max(), self.ev())
+        format!("{}:\n\tmin: {}\n\tmax: {}\n\tev : {}\n\tvar: {}\n\tstd: {}",
+                self, self.min(), self.max(), self.ev(),
+                self.variance(), self.stddev())
+    }
+
+    /// Display the probability distribution, one total per line,
+    /// with its probability and an ASCII histogram bar scaled to
+    /// the most likely total.
+    pub fn distribution_report(&self) -> String {
+        let dist = self.distribution();
+        let max_p = dist.iter().map(|&(_, p)| p).fold(0.0, f64::max);
+        let mut out = format!("{}:\n", self);
+        for (total, p) in dist {
+            let bars = if max_p > 0.0 {
+                (p / max_p * 40.0).round() as usize
+            } else {
+                0
+            };
+            out.push_str(&format!("\t{:>5}: {:6.2}% {}\n",
+                                  total, p * 100.0, "#".repeat(bars)));
+        }
+        // Drop the trailing newline; the caller's println! adds one.
+        out.pop();
+        out
     }
 }
 
-/// Convert a roll into the `XdY+Z` notation.
-impl fmt::Display for Roll {
+/// Reconstruct the canonical `XdY+...` notation of an expression.
+impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}d{}", self.num_dice, self.num_faces)?;
-        if self.extra != 0 {
-            write!(f, "{:+}", self.extra)?;
+        match *self {
+            Expr::Dice(ref r) => write!(f, "{}", r),
+            Expr::Constant(c) => write!(f, "{}", c),
+            Expr::Add(ref a, ref b) => write!(f, "{}+{}", a, b),
+            Expr::Sub(ref a, ref b) => write!(f, "{}-{}", a, b),
+        }
+    }
+}
+
+// Render the dice of one roll for a simulation report. When every
+// die counts the format matches the plain `[3, 2, 3, 1]` debug
+// output; when a keep/drop modifier is in play the dropped dice are
+// parenthesized, e.g. `[3, 2, 3, (1)]`, so the kept total is clear.
+fn format_rolled(rolled: &[u16], mask: &[bool]) -> String {
+    let parts: Vec<String> = rolled.iter().zip(mask)
+        .map(|(&d, &keep)| if keep { d.to_string() } else { format!("({})", d) })
+        .collect();
+    format!("[{}]", parts.join(", "))
+}
+
+/// Convolve two probability distributions, adding (`sign == 1`)
+/// or subtracting (`sign == -1`) the right-hand totals. The
+/// result is aggregated by total and returned in increasing order.
+fn convolve(a: &[(i32, f64)], b: &[(i32, f64)], sign: i32) -> Vec<(i32, f64)> {
+    use std::collections::BTreeMap;
+    let mut acc: BTreeMap<i32, f64> = BTreeMap::new();
+    for &(ta, pa) in a {
+        for &(tb, pb) in b {
+            *acc.entry(ta + sign * tb).or_insert(0.0) += pa * pb;
+        }
+    }
+    acc.into_iter().collect()
+}
+
+/// The binomial coefficient `n choose k` as an `f64`.
+fn binom(n: u16, k: u16) -> f64 {
+    if k > n { return 0.0; }
+    let k = k.min(n - k);
+    let mut result = 1.0f64;
+    for i in 0 .. k {
+        result *= (n - i) as f64 / (i + 1) as f64;
+    }
+    result
+}
+
+/// Distribution of the sum of the `keep` extreme dice among
+/// `num_dice` dice of `num_faces` faces — the highest `keep` when
+/// `highest` is true, otherwise the lowest `keep`.
+///
+/// Values are considered from the kept end inwards. Conditioning
+/// a yet-unplaced die on being on the far side of the current
+/// value makes "how many dice show this value" a binomial draw,
+/// so the kept sum is accumulated exactly by a small DP over the
+/// `(dice placed, kept sum)` state.
+fn kept_sum_distribution(num_dice: u16, num_faces: u16, keep: u16, highest: bool)
+    -> Vec<(i32, f64)>
+{
+    use std::collections::BTreeMap;
+
+    let values: Vec<u16> = if highest {
+        (1 ..= num_faces).rev().collect()
+    } else {
+        (1 ..= num_faces).collect()
+    };
+
+    // state: (dice placed so far, kept sum so far) -> probability
+    let mut states: BTreeMap<(u16, i32), f64> = BTreeMap::new();
+    states.insert((0, 0), 1.0);
+
+    for &v in values.iter() {
+        // Probability a still-unplaced die shows exactly `v`, given
+        // it lies on the kept side of (or at) `v`.
+        let denom = if highest { v } else { num_faces - v + 1 };
+        let p = 1.0 / denom as f64;
+
+        let mut next: BTreeMap<(u16, i32), f64> = BTreeMap::new();
+        for (&(placed, ksum), &prob) in states.iter() {
+            let rem = num_dice - placed;
+            for n in 0 ..= rem {
+                let bp = binom(rem, n)
+                    * p.powi(n as i32)
+                    * (1.0 - p).powi((rem - n) as i32);
+                if bp == 0.0 { continue; }
+                let slots = if keep > placed { keep - placed } else { 0 };
+                let kept = if n < slots { n } else { slots };
+                let entry = next.entry((placed + n, ksum + kept as i32 * v as i32))
+                    .or_insert(0.0);
+                *entry += prob * bp;
+            }
+        }
+        states = next;
+    }
+
+    // Every surviving state has placed all the dice; collapse to
+    // the kept-sum marginal.
+    let mut out: BTreeMap<i32, f64> = BTreeMap::new();
+    for (&(_, ksum), &prob) in states.iter() {
+        *out.entry(ksum).or_insert(0.0) += prob;
+    }
+    out.into_iter().collect()
+}
+
+/// A success-counting dice pool.
+///
+/// Unlike a `Roll`, a pool does not sum the pips; it counts how
+/// many dice meet a success `threshold` (a die succeeds when its
+/// value is `>= threshold`), the way the Chronicles of Darkness
+/// system does. An optional `explode` threshold grants an extra
+/// die for every face that rolls `>= explode` ("10-again").
+///
+/// A pool is written `NdYtT`, with an optional `eE` suffix for
+/// exploding dice, e.g. `10d10t8` or `10d10t8e10`.
+#[derive(Debug, PartialEq)]
+pub struct Pool {
+    num_dice: u16,
+    num_faces: u16,
+    threshold: u16,
+    explode: Option<u16>,
+}
+
+impl Pool {
+    /// Create a new pool, validating that the success threshold
+    /// lies within `1 ..= num_faces` and the explosion threshold
+    /// within `2 ..= num_faces` — an explosion threshold of `1`
+    /// would make every die always explode, giving an infinite
+    /// expected count, so it is rejected as out of range.
+    pub fn new(num_dice: u16, num_faces: u16, threshold: u16,
+               explode: Option<u16>) -> Result<Self, EvError> {
+        if threshold < 1 || threshold > num_faces {
+            return Err(EvError::ThresholdOutOfRange);
+        }
+        if let Some(e) = explode {
+            if e < 2 || e > num_faces {
+                return Err(EvError::ThresholdOutOfRange);
+            }
+        }
+        Ok(Pool {
+            num_dice: num_dice,
+            num_faces: num_faces,
+            threshold: threshold,
+            explode: explode,
+        })
+    }
+
+    // Probability that a single die is a success.
+    fn success_prob(&self) -> f64 {
+        (self.num_faces - self.threshold + 1) as f64 / self.num_faces as f64
+    }
+
+    /// Compute the expected number of successes.
+    ///
+    /// For a non-exploding pool this is simply `N` times the
+    /// per-die success probability. Exploding dice each spawn
+    /// further dice with probability `p_explode`, so the count is
+    /// multiplied by the geometric factor `1 / (1 - p_explode)`.
+    pub fn expected_successes(&self) -> f64 {
+        let base = self.num_dice as f64 * self.success_prob();
+        match self.explode {
+            Some(e) => {
+                let p_explode =
+                    (self.num_faces - e + 1) as f64 / self.num_faces as f64;
+                base / (1.0 - p_explode)
+            }
+            None => base,
+        }
+    }
+
+    /// Compute the distribution over success counts for the base
+    /// pool by convolving the per-die success/failure Bernoulli
+    /// outcomes. Extra dice from explosions are reflected in
+    /// `expected_successes` but not in this base distribution.
+    pub fn success_distribution(&self) -> Vec<(u32, f64)> {
+        let p = self.success_prob();
+        // dp[k] is the probability of exactly k successes so far.
+        let mut dp = vec![1.0f64];
+        for _ in 0 .. self.num_dice {
+            let mut next = vec![0.0f64; dp.len() + 1];
+            for (k, &pk) in dp.iter().enumerate() {
+                next[k] += pk * (1.0 - p);
+                next[k + 1] += pk * p;
+            }
+            dp = next;
+        }
+        dp.into_iter().enumerate().map(|(k, pr)| (k as u32, pr)).collect()
+    }
+
+    /// Display the expected successes on a single line.
+    pub fn print(&self) -> String {
+        format!("{} {}", self, self.expected_successes())
+    }
+
+    /// Display the expected successes and the success-count
+    /// distribution on multiple lines.
+    pub fn pretty_print(&self) -> String {
+        let dist = self.success_distribution();
+        let max_p = dist.iter().map(|&(_, p)| p).fold(0.0, f64::max);
+        let mut out = format!("{}:\n\tev : {}\n", self, self.expected_successes());
+        for (successes, p) in dist {
+            let bars = if max_p > 0.0 {
+                (p / max_p * 40.0).round() as usize
+            } else {
+                0
+            };
+            out.push_str(&format!("\t{:>5}: {:6.2}% {}\n",
+                                  successes, p * 100.0, "#".repeat(bars)));
+        }
+        // Drop the trailing newline; the caller's println! adds one.
+        out.pop();
+        out
+    }
+}
+
+/// Reconstruct the canonical `NdYtT[eE]` pool notation.
+impl fmt::Display for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}d{}t{}", self.num_dice, self.num_faces, self.threshold)?;
+        if let Some(e) = self.explode {
+            write!(f, "e{}", e)?;
         }
         return Ok(());
     }
@@ -165,7 +725,9 @@ fn usage(opts: &Options, progname: &str) {
     let brief = format!(
         concat!(
             "Usage: {} [options] [rolls ...]\n",
-            "\troll: XdY, XdY+Z, XdY-Z (e.g. 1d6, 2d4+1, 3d8-1)"),
+            "\troll: terms joined by + and -, each XdY or a constant\n",
+            "\t      (e.g. 1d6, 2d4+1, 3d8-1, 2d6+1d8+3, 4d6-1d4)\n",
+            "\t      counts, faces and bonuses may be -D variables (e.g. Nd6+STR)"),
         progname
     );
     print!("{}", opts.usage(&brief));
@@ -182,50 +744,243 @@ fn read_digits(s: &str) -> usize {
     return i;
 }
 
-fn parse(mut roll_desc: &str) -> Result<Roll, EvError> {
-    let i = read_digits(roll_desc);
+/// Read an integer that is either a decimal literal or the name
+/// of a variable to be resolved from `env`. Variable names are an
+/// uppercase letter or underscore followed by uppercase letters,
+/// digits or underscores, which keeps them distinct from the
+/// lowercase `d` separator.
+///
+/// `empty_err` is returned when nothing readable is present and
+/// `overflow_err` when a literal has too many digits.
+fn read_value<'a>(s: &'a str, env: &HashMap<String, i32>,
+                  empty_err: EvError, overflow_err: EvError)
+    -> Result<(i32, &'a str), EvError>
+{
+    let bytes = s.as_bytes();
+    if bytes.is_empty() {
+        return Err(empty_err);
+    }
+
+    let c = bytes[0];
+    if c >= b'0' && c <= b'9' {
+        let i = read_digits(s);
+        if i >= MAX_DIGITS { return Err(overflow_err); }
+        let v = s[0 .. i].parse::<i32>().or(Err(overflow_err))?;
+        Ok((v, &s[i..]))
+    } else if (c >= b'A' && c <= b'Z') || c == b'_' {
+        let mut j = 0;
+        for &b in bytes {
+            let is_ident = (b >= b'A' && b <= b'Z')
+                || (b >= b'0' && b <= b'9')
+                || b == b'_';
+            if is_ident { j += 1; } else { break; }
+        }
+        let name = &s[0 .. j];
+        match env.get(name) {
+            Some(&v) => Ok((v, &s[j..])),
+            None => Err(EvError::UndefinedVariable(name.to_string())),
+        }
+    } else {
+        Err(empty_err)
+    }
+}
+
+// Narrow a resolved value to a dice/face count, mapping an
+// out-of-range value to the given error.
+fn to_u16(v: i32, err: EvError) -> Result<u16, EvError> {
+    if v < 0 || v > u16::max_value() as i32 { Err(err) } else { Ok(v as u16) }
+}
+
+// Narrow a resolved value to a bonus, mapping an out-of-range
+// value to the given error.
+fn to_i16(v: i32, err: EvError) -> Result<i16, EvError> {
+    if v < i16::min_value() as i32 || v > i16::max_value() as i32 {
+        Err(err)
+    } else {
+        Ok(v as i16)
+    }
+}
+
+/// Parse a single term — a dice roll `XdY` or a bare integer
+/// constant — and return it together with the unconsumed
+/// remainder of the input. Dice count, face count and constants
+/// may each be a named variable resolved from `env`.
+///
+/// `empty_err` is reported when the term has no leading number
+/// (the first term of an expression is missing its dice count,
+/// whereas a term after a `+`/`-` is an empty term), and
+/// `overflow_err` when that number is out of range (the dice count
+/// of the first term overflows, whereas a later constant is an
+/// over-large bonus).
+fn parse_term<'a>(s: &'a str, env: &HashMap<String, i32>,
+                  empty_err: EvError, overflow_err: EvError)
+    -> Result<(Expr, &'a str), EvError>
+{
+    let (n, rest) = read_value(s, env, empty_err, overflow_err.clone())?;
+
+    if rest.starts_with('d') {
+        let rest = &rest[1..];
+        let (nf, rest) =
+            read_value(rest, env, EvError::MissingNumberOfSides, EvError::TooManySides)?;
+        let nd = to_u16(n, overflow_err)?;
+        let nf = to_u16(nf, EvError::TooManySides)?;
+        let (keep, rest) = parse_keep(rest, nd)?;
+        Ok((Expr::Dice(Roll::new(nd, nf, 0).with_keep(keep)), rest))
+    } else {
+        let c = to_i16(n, overflow_err)?;
+        Ok((Expr::Constant(c), rest))
+    }
+}
+
+/// Parse an optional keep/drop modifier following the sides of a
+/// dice term: `kN` (keep highest), `klN` (keep lowest), `dhN`
+/// (drop highest), `dlN` (drop lowest). `num_dice` is the dice
+/// count, used to normalize drops into keeps and to reject counts
+/// that exceed the roll.
+fn parse_keep(s: &str, num_dice: u16) -> Result<(Keep, &str), EvError> {
+    if s.starts_with('k') {
+        let s = &s[1..];
+        let lowest = s.starts_with('l');
+        let s = if lowest { &s[1..] } else { s };
+        let (count, s) = read_keep_count(s)?;
+        if count > num_dice { return Err(EvError::KeepTooLarge); }
+        let keep = if lowest { Keep::Lowest(count) } else { Keep::Highest(count) };
+        Ok((keep, s))
+    } else if s.starts_with('d') {
+        let s = &s[1..];
+        let highest = if s.starts_with('h') {
+            true
+        } else if s.starts_with('l') {
+            false
+        } else {
+            return Err(EvError::InvalidFormat);
+        };
+        let (count, s) = read_keep_count(&s[1..])?;
+        if count > num_dice { return Err(EvError::KeepTooLarge); }
+        // Dropping the highest `count` keeps the lowest rest, and
+        // vice-versa.
+        let kept = num_dice - count;
+        let keep = if highest { Keep::Lowest(kept) } else { Keep::Highest(kept) };
+        Ok((keep, s))
+    } else {
+        Ok((Keep::All, s))
+    }
+}
+
+// Read the count following a keep/drop letter.
+fn read_keep_count(s: &str) -> Result<(u16, &str), EvError> {
+    let i = read_digits(s);
+    if i == 0 { return Err(EvError::InvalidFormat); }
+    if i >= MAX_DIGITS { return Err(EvError::KeepTooLarge); }
+    let count = s[0 .. i].parse::<u16>().or(Err(EvError::KeepTooLarge))?;
+    Ok((count, &s[i..]))
+}
+
+fn parse(roll_desc: &str, env: &HashMap<String, i32>) -> Result<Expr, EvError> {
+    let (mut expr, mut rest) =
+        parse_term(roll_desc, env, EvError::MissingNumberOfDice, EvError::TooManyDice)?;
+
+    while !rest.is_empty() {
+        let op = rest.as_bytes()[0];
+        if op != b'+' && op != b'-' {
+            return Err(EvError::InvalidFormat);
+        }
+        rest = &rest[1..];
+        // A trailing operator is a missing bonus; anything else that
+        // does not start a term is an empty term.
+        if rest.is_empty() {
+            return Err(EvError::MissingExtra);
+        }
+        let (term, next) =
+            parse_term(rest, env, EvError::EmptyTerm, EvError::ExtraTooLarge)?;
+        expr = if op == b'+' {
+            Expr::Add(Box::new(expr), Box::new(term))
+        } else {
+            Expr::Sub(Box::new(expr), Box::new(term))
+        };
+        rest = next;
+    }
+
+    return Ok(expr);
+}
+
+
+/// Parse a success-counting dice pool written `NdYtT` with an
+/// optional `eE` exploding suffix.
+fn parse_pool(s: &str) -> Result<Pool, EvError> {
+    let i = read_digits(s);
     if i == 0 { return Err(EvError::MissingNumberOfDice); }
     if i >= MAX_DIGITS { return Err(EvError::TooManyDice); }
-    let nd = roll_desc[0 .. i].parse::<u16>().or(Err(EvError::TooManyDice))?;
-    roll_desc = &roll_desc[i..];
+    let nd = s[0 .. i].parse::<u16>().or(Err(EvError::TooManyDice))?;
+    let s = &s[i..];
 
-    if !roll_desc.starts_with('d') {
-        return Err(EvError::InvalidFormat);
-    }
-    roll_desc = &roll_desc[1..];
+    if !s.starts_with('d') { return Err(EvError::InvalidFormat); }
+    let s = &s[1..];
 
-    let i = read_digits(roll_desc);
+    let i = read_digits(s);
     if i == 0 { return Err(EvError::MissingNumberOfSides); }
     if i >= MAX_DIGITS { return Err(EvError::TooManySides); }
-    let nf = roll_desc[0 .. i].parse::<u16>().or(Err(EvError::TooManySides))?;
-    roll_desc = &roll_desc[i..];
+    let nf = s[0 .. i].parse::<u16>().or(Err(EvError::TooManySides))?;
+    let s = &s[i..];
+
+    if !s.starts_with('t') { return Err(EvError::InvalidFormat); }
+    let s = &s[1..];
 
-    let mut extra = 0;
-    if roll_desc.starts_with('+') || roll_desc.starts_with('-') {
-        let i = read_digits(&roll_desc[1..]);
-        if i == 0 { return Err(EvError::MissingExtra); }
-        if i >= MAX_DIGITS { return Err(EvError::ExtraTooLarge); }
-        extra = roll_desc[0 .. i+1].parse::<i16>().or(Err(EvError::ExtraTooLarge))?;
-        roll_desc = &roll_desc[i+1 ..];
+    let i = read_digits(s);
+    if i == 0 { return Err(EvError::InvalidFormat); }
+    if i >= MAX_DIGITS { return Err(EvError::ThresholdOutOfRange); }
+    let threshold = s[0 .. i].parse::<u16>().or(Err(EvError::ThresholdOutOfRange))?;
+    let mut s = &s[i..];
+
+    let mut explode = None;
+    if s.starts_with('e') {
+        let rest = &s[1..];
+        let i = read_digits(rest);
+        if i == 0 { return Err(EvError::InvalidFormat); }
+        if i >= MAX_DIGITS { return Err(EvError::ThresholdOutOfRange); }
+        let e = rest[0 .. i].parse::<u16>().or(Err(EvError::ThresholdOutOfRange))?;
+        explode = Some(e);
+        s = &rest[i..];
     }
 
-    if !roll_desc.is_empty() {
+    if !s.is_empty() {
         return Err(EvError::InvalidFormat);
     }
 
-    return Ok(Roll::new(nd, nf, extra));
+    return Pool::new(nd, nf, threshold, explode);
 }
 
+/// Parse a pool expression and print its statistics.
+fn parse_and_print_pool(line: &str, output_style: &OutputStyle) {
+    match parse_pool(line) {
+        Ok(pool) => {
+            match *output_style {
+                OutputStyle::SingleLine => println!("{}", pool.print()),
+                _ => println!("{}", pool.pretty_print()),
+            }
+        }
+        Err(ev_error) => {
+            errmsg(&format!("{}: {}", ev_error, line));
+        }
+    }
+}
 
-fn parse_and_print(line: &str, output_style: &OutputStyle) {
-    match parse(line) {
-        Ok(roll) => {
+fn parse_and_print<R: Rng>(line: &str, output_style: &OutputStyle,
+                           env: &HashMap<String, i32>, rng: &mut R) {
+    match parse(line, env) {
+        Ok(expr) => {
             match *output_style {
                 OutputStyle::SingleLine => {
-                    println!("{}", roll.print());
+                    println!("{}", expr.print());
                 }
                 OutputStyle::MultiLine => {
-                    println!("{}", roll.pretty_print());
+                    println!("{}", expr.pretty_print());
+                }
+                OutputStyle::Distribution => {
+                    println!("{}", expr.distribution_report());
+                }
+                OutputStyle::Roll => {
+                    println!("{}", expr.roll_report(rng));
                 }
             }
         }
@@ -240,6 +995,12 @@ fn main() {
     let argv: Vec<String> = args().collect();
     let mut opts = Options::new();
     opts.optflag("s", "single-line", "single line display");
+    opts.optflag("d", "dist", "display the probability distribution");
+    opts.optflag("r", "roll", "simulate the roll with a random number generator");
+    opts.optflag("p", "pool", "treat inputs as success-counting dice pools (NdYtT)");
+    opts.optmulti("D", "define",
+                  "define a variable usable in rolls (NAME is uppercase)",
+                  "NAME=VALUE");
     opts.optflag("h", "help", "display this help message");
     opts.optflag("v", "version", "display version number");
 
@@ -262,29 +1023,86 @@ fn main() {
     }
 
     let output_style =
-        if matches.opt_present("s") {
+        if matches.opt_present("r") {
+            OutputStyle::Roll
+        } else if matches.opt_present("d") {
+            OutputStyle::Distribution
+        } else if matches.opt_present("s") {
             OutputStyle::SingleLine
         } else {
             OutputStyle::MultiLine
         };
 
+    let pool_mode = matches.opt_present("p");
+
+    // Collect the user-defined variables from repeated -D flags.
+    let mut env: HashMap<String, i32> = HashMap::new();
+    for def in matches.opt_strs("D") {
+        match parse_definition(&def) {
+            Some((name, value)) => { env.insert(name, value); }
+            None => {
+                errmsg(&format!("invalid definition: {}", def));
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut rng = rand::thread_rng();
+
     // Read the rolls from the positional command-line
     // arguments if there are any, otherwise read rolls
     // from stdin.
     if !matches.free.is_empty() {
         for arg in matches.free.iter() {
-            parse_and_print(arg, &output_style);
+            if pool_mode {
+                parse_and_print_pool(arg, &output_style);
+            } else {
+                parse_and_print(arg, &output_style, &env, &mut rng);
+            }
         }
     } else {
         let stdin = io::stdin();
         let mut buf = String::with_capacity(32);
         while stdin.read_line(&mut buf).unwrap() > 0 {
-            parse_and_print(buf.trim(), &output_style);
+            if pool_mode {
+                parse_and_print_pool(buf.trim(), &output_style);
+            } else {
+                parse_and_print(buf.trim(), &output_style, &env, &mut rng);
+            }
             buf.clear();
         }
     }
 }
 
+/// Split a `NAME=VALUE` definition, returning the name and its
+/// integer value, or `None` if it is malformed. The name must be a
+/// valid identifier — an uppercase letter or `_` followed by
+/// uppercase letters, digits or `_` — so that it matches the grammar
+/// `read_value` uses when resolving variables in a roll; a name that
+/// could never be referenced is rejected.
+fn parse_definition(def: &str) -> Option<(String, i32)> {
+    let mut parts = def.splitn(2, '=');
+    let name = parts.next()?;
+    let value = parts.next()?;
+    if !is_variable_name(name) {
+        return None;
+    }
+    value.parse::<i32>().ok().map(|v| (name.to_string(), v))
+}
+
+// Whether `name` is a legal variable identifier: a non-empty run of
+// uppercase letters, digits and `_`, not starting with a digit.
+fn is_variable_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    match bytes.first() {
+        Some(&c) if (c >= b'A' && c <= b'Z') || c == b'_' => {}
+        _ => return false,
+    }
+    bytes.iter().all(|&b| {
+        (b >= b'A' && b <= b'Z') || (b >= b'0' && b <= b'9') || b == b'_'
+    })
+}
+
 #[test]
 fn test_roll() {
     let r = Roll::new(1, 6, 0);
@@ -308,6 +1126,184 @@ fn test_roll() {
     assert_eq!(r.ev(), 2.5);
 }
 
+#[test]
+fn test_distribution() {
+    // A single d6 is uniform over 1..=6.
+    let r = Roll::new(1, 6, 0);
+    let dist = r.distribution();
+    assert_eq!(dist.len(), 6);
+    for (i, &(total, p)) in dist.iter().enumerate() {
+        assert_eq!(total, i as i32 + 1);
+        assert!((p - 1.0 / 6.0).abs() < 1e-9);
+    }
+
+    // 2d6 ranges over 2..=12 and its probabilities sum to 1.
+    let r = Roll::new(2, 6, 0);
+    let dist = r.distribution();
+    assert_eq!(dist.first().unwrap().0, 2);
+    assert_eq!(dist.last().unwrap().0, 12);
+    let sum: f64 = dist.iter().map(|&(_, p)| p).sum();
+    assert!((sum - 1.0).abs() < 1e-9);
+    // 7 is the most likely total: 6/36.
+    let seven = dist.iter().find(|&&(t, _)| t == 7).unwrap().1;
+    assert!((seven - 6.0 / 36.0).abs() < 1e-9);
+
+    // The extra just shifts every total.
+    let r = Roll::new(1, 6, 2);
+    let dist = r.distribution();
+    assert_eq!(dist.first().unwrap().0, 3);
+    assert_eq!(dist.last().unwrap().0, 8);
+}
+
+#[test]
+fn test_roll_rng() {
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    let expr = parse("2d6+3", &HashMap::new()).unwrap();
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let total = expr.roll(&mut rng);
+    // A simulated total always lands in the achievable range.
+    assert!(total >= expr.min() as i32);
+    assert!(total <= expr.max() as i32);
+
+    // The same seed yields the same total: the RNG is injectable.
+    let mut rng = StdRng::seed_from_u64(42);
+    assert_eq!(total, expr.roll(&mut rng));
+
+    // Every individual die is within 1..=num_faces.
+    let mut rng = StdRng::seed_from_u64(7);
+    for die in Roll::new(4, 8, 0).roll(&mut rng) {
+        assert!(die >= 1 && die <= 8);
+    }
+
+    // A keep modifier is honoured by the simulation: only the kept
+    // dice contribute to the total, and the report parenthesizes the
+    // dropped die.
+    let keep = parse("4d6k3", &HashMap::new()).unwrap();
+    let mut rng = StdRng::seed_from_u64(42);
+    let total = keep.roll(&mut rng);
+    assert!(total >= keep.min() as i32);
+    assert!(total <= keep.max() as i32); // at most three sixes = 18
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let report = keep.roll_report(&mut rng);
+    // Exactly one of four dice is dropped (shown in parentheses).
+    assert_eq!(report.matches('(').count(), 1);
+    assert!(report.ends_with(&format!("=> {}", total)));
+}
+
+#[test]
+fn test_pool() {
+    // 10d10, success on >= 8: p = 3/10 per die.
+    let pool = Pool::new(10, 10, 8, None).unwrap();
+    assert!((pool.expected_successes() - 3.0).abs() < 1e-9);
+
+    // The success-count distribution is a binomial and sums to 1.
+    let dist = pool.success_distribution();
+    assert_eq!(dist.len(), 11); // 0..=10 successes
+    let sum: f64 = dist.iter().map(|&(_, p)| p).sum();
+    assert!((sum - 1.0).abs() < 1e-9);
+
+    // Exploding on 10 multiplies the expectation by 1/(1 - 1/10).
+    let pool = Pool::new(10, 10, 8, Some(10)).unwrap();
+    assert!((pool.expected_successes() - 3.0 / 0.9).abs() < 1e-9);
+
+    // Out-of-range thresholds are rejected.
+    assert_eq!(Pool::new(5, 10, 0, None), Err(EvError::ThresholdOutOfRange));
+    assert_eq!(Pool::new(5, 10, 11, None), Err(EvError::ThresholdOutOfRange));
+    assert_eq!(Pool::new(5, 10, 8, Some(11)), Err(EvError::ThresholdOutOfRange));
+    // An explosion threshold of 1 would never terminate; reject it
+    // rather than report an infinite expected count.
+    assert_eq!(Pool::new(5, 10, 8, Some(1)), Err(EvError::ThresholdOutOfRange));
+
+    // Parsing round-trips the canonical notation.
+    assert_eq!(parse_pool("10d10t8e10"),
+               Ok(Pool::new(10, 10, 8, Some(10)).unwrap()));
+    assert_eq!(format!("{}", parse_pool("10d10t8").unwrap()), "10d10t8");
+    assert_eq!(parse_pool("10d10t0"), Err(EvError::ThresholdOutOfRange));
+    assert_eq!(parse_pool("10d10"), Err(EvError::InvalidFormat));
+}
+
+#[test]
+fn test_keep_drop() {
+    let env = HashMap::new();
+    // 4d6k3: keep the highest three of four d6.
+    let expr = parse("4d6k3", &env).unwrap();
+    assert_eq!(format!("{}", expr), "4d6k3");
+    assert_eq!(expr.min(), 3.0);  // three ones
+    assert_eq!(expr.max(), 18.0); // three sixes
+    let dist = match expr { Expr::Dice(ref r) => r.distribution(), _ => unreachable!() };
+    let sum: f64 = dist.iter().map(|&(_, p)| p).sum();
+    assert!((sum - 1.0).abs() < 1e-9);
+    // The classic 4d6-drop-lowest average is ~12.2446.
+    assert!((expr.ev() as f64 - 12.244598765).abs() < 1e-6);
+
+    // Drop notation normalizes to the equivalent keep notation.
+    assert_eq!(format!("{}", parse("4d6dl1", &env).unwrap()), "4d6k3");
+    assert_eq!(format!("{}", parse("4d6dh1", &env).unwrap()), "4d6kl3");
+
+    // 5e advantage: keep the higher of two d20 beats a flat d20.
+    let adv = parse("2d20k1", &env).unwrap();
+    assert_eq!(adv.min(), 1.0);
+    assert_eq!(adv.max(), 20.0);
+    assert!(adv.ev() > 10.5);
+
+    // Keeping/dropping more dice than rolled is rejected.
+    assert_eq!(parse("4d6k5", &env), Err(EvError::KeepTooLarge));
+    assert_eq!(parse("4d6dl5", &env), Err(EvError::KeepTooLarge));
+}
+
+#[test]
+fn test_variance() {
+    let env = HashMap::new();
+    // A single d6: variance (36 - 1)/12 = 35/12.
+    let r = Roll::new(1, 6, 0);
+    assert!((r.variance() - 35.0 / 12.0).abs() < 1e-9);
+    assert!((r.stddev() - (35.0f64 / 12.0).sqrt()).abs() < 1e-9);
+
+    // Variance scales with the dice and ignores the extra.
+    let r = Roll::new(2, 6, 3);
+    assert!((r.variance() - 2.0 * 35.0 / 12.0).abs() < 1e-9);
+
+    // 1d12 and 2d6 have similar ev but very different spread.
+    let d12 = parse("1d12", &env).unwrap();
+    let two_d6 = parse("2d6", &env).unwrap();
+    assert!(d12.variance() > two_d6.variance());
+
+    // Independent terms add their variances; constants add none.
+    let expr = parse("2d6+1d8+3", &env).unwrap();
+    let expected = 2.0 * 35.0 / 12.0 + (64.0 - 1.0) / 12.0;
+    assert!((expr.variance() - expected).abs() < 1e-9);
+}
+
+#[test]
+fn test_variables() {
+    let mut env = HashMap::new();
+    env.insert("N".to_string(), 2);
+    env.insert("STR".to_string(), 3);
+
+    // Variables may stand in for the dice count, faces or bonus.
+    let expr = parse("Nd6+STR", &env).unwrap();
+    assert_eq!(format!("{}", expr), "2d6+3");
+    assert_eq!(expr.ev(), 10.0);
+
+    // Unknown variables are reported by name.
+    assert_eq!(parse("1dDEX", &env),
+               Err(EvError::UndefinedVariable("DEX".to_string())));
+
+    // A `NAME=VALUE` definition is split into its parts.
+    assert_eq!(parse_definition("STR=3"), Some(("STR".to_string(), 3)));
+    assert_eq!(parse_definition("STR=-1"), Some(("STR".to_string(), -1)));
+    assert_eq!(parse_definition("STR"), None);
+    assert_eq!(parse_definition("=3"), None);
+    // A lowercase name could never be referenced in a roll, so it is
+    // rejected rather than silently defined.
+    assert_eq!(parse_definition("foo=3"), None);
+    assert_eq!(parse_definition("3X=3"), None);
+}
+
 #[test]
 fn test_print() {
     let r = Roll::new(1, 6, 0);
@@ -320,26 +1316,60 @@ fn test_print() {
 
 #[test]
 fn test_parse() {
-    assert_eq!(parse(""), Err(EvError::MissingNumberOfDice));
-    assert_eq!(parse("d"), Err(EvError::MissingNumberOfDice));
-    assert_eq!(parse("5d"), Err(EvError::MissingNumberOfSides));
-    assert_eq!(parse("d5"), Err(EvError::MissingNumberOfDice));
-    assert_eq!(parse("+5"), Err(EvError::MissingNumberOfDice));
-    assert_eq!(parse("-5"), Err(EvError::MissingNumberOfDice));
-    assert_eq!(parse("XdY"), Err(EvError::MissingNumberOfDice));
-    assert_eq!(parse("123456d2"), Err(EvError::TooManyDice));
-    assert_eq!(parse("1d123456"), Err(EvError::TooManySides));
-    assert_eq!(parse("1d2+123456"), Err(EvError::ExtraTooLarge));
-    assert_eq!(parse("1d2-123456"), Err(EvError::ExtraTooLarge));
-
-    assert_eq!(parse("99999d2"), Err(EvError::TooManyDice));
-    assert_eq!(parse("2d99999"), Err(EvError::TooManySides));
-    assert_eq!(parse("1d6+99999"), Err(EvError::ExtraTooLarge));
-    assert_eq!(parse("1d6-99999"), Err(EvError::ExtraTooLarge));
-
-    assert_eq!(parse("3x4"), Err(EvError::InvalidFormat));
-    assert_eq!(parse("3d4+"), Err(EvError::MissingExtra));
-    assert_eq!(parse("3d4-"), Err(EvError::MissingExtra));
-    assert_eq!(parse("3d4*4"), Err(EvError::InvalidFormat));
-    assert_eq!(parse("3x4/4"), Err(EvError::InvalidFormat));
+    let env = HashMap::new();
+    assert_eq!(parse("", &env), Err(EvError::MissingNumberOfDice));
+    assert_eq!(parse("d", &env), Err(EvError::MissingNumberOfDice));
+    assert_eq!(parse("5d", &env), Err(EvError::MissingNumberOfSides));
+    assert_eq!(parse("d5", &env), Err(EvError::MissingNumberOfDice));
+    assert_eq!(parse("+5", &env), Err(EvError::MissingNumberOfDice));
+    assert_eq!(parse("-5", &env), Err(EvError::MissingNumberOfDice));
+    // `X` now reads as an (undefined) variable rather than garbage.
+    assert_eq!(parse("XdY", &env),
+               Err(EvError::UndefinedVariable("X".to_string())));
+    assert_eq!(parse("123456d2", &env), Err(EvError::TooManyDice));
+    assert_eq!(parse("1d123456", &env), Err(EvError::TooManySides));
+    assert_eq!(parse("1d2+123456", &env), Err(EvError::ExtraTooLarge));
+    assert_eq!(parse("1d2-123456", &env), Err(EvError::ExtraTooLarge));
+
+    assert_eq!(parse("99999d2", &env), Err(EvError::TooManyDice));
+    assert_eq!(parse("2d99999", &env), Err(EvError::TooManySides));
+    assert_eq!(parse("1d6+99999", &env), Err(EvError::ExtraTooLarge));
+    assert_eq!(parse("1d6-99999", &env), Err(EvError::ExtraTooLarge));
+
+    assert_eq!(parse("3x4", &env), Err(EvError::InvalidFormat));
+    assert_eq!(parse("3d4+", &env), Err(EvError::MissingExtra));
+    assert_eq!(parse("3d4-", &env), Err(EvError::MissingExtra));
+    assert_eq!(parse("3d4*4", &env), Err(EvError::InvalidFormat));
+    assert_eq!(parse("3x4/4", &env), Err(EvError::InvalidFormat));
+}
+
+#[test]
+fn test_parse_compound() {
+    let env = HashMap::new();
+    // A single term is a bare dice node or constant.
+    assert_eq!(parse("2d6", &env), Ok(Expr::Dice(Roll::new(2, 6, 0))));
+    assert_eq!(parse("5", &env), Ok(Expr::Constant(5)));
+
+    // A bonus becomes a constant term joined with `+`/`-`.
+    assert_eq!(parse("2d4+1", &env),
+               Ok(Expr::Add(Box::new(Expr::Dice(Roll::new(2, 4, 0))),
+                            Box::new(Expr::Constant(1)))));
+
+    // Several terms associate to the left.
+    let expr = parse("2d6+1d8+3", &env).unwrap();
+    assert_eq!(format!("{}", expr), "2d6+1d8+3");
+    assert_eq!(expr.ev(), 7.0 + 4.5 + 3.0);
+    assert_eq!(expr.min(), 2.0 + 1.0 + 3.0);
+    assert_eq!(expr.max(), 12.0 + 8.0 + 3.0);
+
+    // Subtraction flips the contribution of the right-hand term.
+    let expr = parse("4d6-1d4", &env).unwrap();
+    assert_eq!(format!("{}", expr), "4d6-1d4");
+    assert_eq!(expr.min(), 4.0 - 4.0);
+    assert_eq!(expr.max(), 24.0 - 1.0);
+
+    // A `+`/`-` with nothing after it is still a missing bonus;
+    // a `+`/`-` followed by a non-term is an empty term.
+    assert_eq!(parse("2d6+", &env), Err(EvError::MissingExtra));
+    assert_eq!(parse("2d6+x", &env), Err(EvError::EmptyTerm));
 }